@@ -1,5 +1,6 @@
-use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use rayon::prelude::*;
+use serde::{Deserialize, Deserializer, Serialize};
 use std::collections::HashMap;
 use std::fs;
 use structopt::StructOpt;
@@ -20,21 +21,41 @@ struct Message {
     IsSaved: bool,
 }
 
-#[derive(Debug, Deserialize)]
-struct ChatData(HashMap<String, Vec<Message>>);
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Text,
+    Json,
+    Csv,
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            other => Err(format!("unknown output format: {}", other)),
+        }
+    }
+}
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "snapchat-analyzer", about = "Analyze Snapchat chat data")]
 struct Opt {
+    /// Glob pattern for one or more chat export files, e.g. "exports/*/chat_history.json"
     #[structopt(short, long)]
     input: String,
 
     #[structopt(short, long)]
     user: Option<String>,
 
+    /// Only include messages on or after this date (YYYY-MM-DD or a full timestamp)
     #[structopt(long)]
     from_date: Option<String>,
 
+    /// Only include messages on or before this date (YYYY-MM-DD or a full timestamp)
     #[structopt(long)]
     to_date: Option<String>,
 
@@ -46,8 +67,93 @@ struct Opt {
 
     #[structopt(long)]
     media_type: Option<String>,
+
+    /// Output format: text, json, or csv
+    #[structopt(long, default_value = "text")]
+    format: Format,
+
+    /// Gap (in hours) beyond which a reply is treated as a new session rather than a reply
+    #[structopt(long, default_value = "6")]
+    session_gap: f64,
+
+    /// Show hour-of-day and day-of-week activity histograms
+    #[structopt(long)]
+    histogram: bool,
+
+    /// Offset from UTC, in hours, used to bucket histogram activity into local time
+    #[structopt(long, default_value = "0")]
+    timezone: i32,
+}
+
+/// Running reply-latency statistics for one user. The mean is accumulated
+/// with Welford's online algorithm; the raw deltas are also kept so the
+/// median and p90 can be reported.
+#[derive(Debug, Serialize)]
+struct ReplyLatency {
+    count: u64,
+    mean_micros: f64,
+    #[serde(skip)]
+    deltas_micros: Vec<i64>,
 }
 
+impl ReplyLatency {
+    fn new() -> Self {
+        ReplyLatency {
+            count: 0,
+            mean_micros: 0.0,
+            deltas_micros: Vec::new(),
+        }
+    }
+
+    fn record(&mut self, delta_micros: i64) {
+        self.count += 1;
+        self.mean_micros += (delta_micros as f64 - self.mean_micros) / self.count as f64;
+        self.deltas_micros.push(delta_micros);
+    }
+
+    fn median_micros(&self) -> Option<i64> {
+        if self.deltas_micros.is_empty() {
+            return None;
+        }
+        let mut sorted = self.deltas_micros.clone();
+        sorted.sort_unstable();
+        Some(sorted[sorted.len() / 2])
+    }
+
+    fn p90_micros(&self) -> Option<i64> {
+        if self.deltas_micros.is_empty() {
+            return None;
+        }
+        let mut sorted = self.deltas_micros.clone();
+        sorted.sort_unstable();
+        let idx = (sorted.len() as f64 * 0.9) as usize;
+        Some(sorted[idx.min(sorted.len() - 1)])
+    }
+}
+
+fn format_duration_micros(micros: i64) -> String {
+    let total_seconds = micros / 1_000_000;
+    let hours = total_seconds / 3600;
+    let minutes = (total_seconds % 3600) / 60;
+    let seconds = total_seconds % 60;
+
+    if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else if minutes > 0 {
+        format!("{}m {}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+/// Renders a single horizontal bar scaled against `max`, e.g. "#### 12".
+fn histogram_bar(count: usize, max: usize) -> String {
+    const WIDTH: usize = 40;
+    let filled = count.checked_mul(WIDTH).and_then(|n| n.checked_div(max)).unwrap_or(0);
+    format!("{} {}", "#".repeat(filled), count)
+}
+
+#[derive(Serialize)]
 struct Statistics {
     total_messages: usize,
     messages_sent: usize,
@@ -55,6 +161,9 @@ struct Statistics {
     saved_messages: usize,
     media_type_counts: HashMap<String, usize>,
     users_interaction_counts: HashMap<String, (usize, usize)>, // (sent, received)
+    reply_latency: HashMap<String, ReplyLatency>,
+    hour_counts: [usize; 24],
+    weekday_counts: [usize; 7],
     earliest_message: Option<DateTime<Utc>>,
     latest_message: Option<DateTime<Utc>>,
 }
@@ -68,133 +177,438 @@ impl Statistics {
             saved_messages: 0,
             media_type_counts: HashMap::new(),
             users_interaction_counts: HashMap::new(),
+            reply_latency: HashMap::new(),
+            hour_counts: [0; 24],
+            weekday_counts: [0; 7],
             earliest_message: None,
             latest_message: None,
         }
     }
 
-    fn update_time_range(&mut self, created: &str) {
-        if let Ok(dt) = DateTime::parse_from_str(created, "%Y-%m-%d %H:%M:%S %Z") {
-            let utc_dt = dt.with_timezone(&Utc);
-            match (self.earliest_message, self.latest_message) {
-                (None, None) => {
-                    self.earliest_message = Some(utc_dt);
-                    self.latest_message = Some(utc_dt);
+    /// Widens the time range and buckets the hour/weekday histograms using
+    /// `message_time`, which callers derive from the precise
+    /// `Created(microseconds)` field rather than the free-form `Created`
+    /// text so this never depends on Snapchat's export date formatting.
+    fn update_time_range(&mut self, message_time: DateTime<Utc>, timezone_offset: i32) {
+        match (self.earliest_message, self.latest_message) {
+            (None, None) => {
+                self.earliest_message = Some(message_time);
+                self.latest_message = Some(message_time);
+            }
+            _ => {
+                if self.earliest_message.is_none_or(|t| message_time < t) {
+                    self.earliest_message = Some(message_time);
                 }
-                _ => {
-                    if self.earliest_message.map_or(true, |t| utc_dt < t) {
-                        self.earliest_message = Some(utc_dt);
-                    }
-                    if self.latest_message.map_or(true, |t| utc_dt > t) {
-                        self.latest_message = Some(utc_dt);
-                    }
+                if self.latest_message.is_none_or(|t| message_time > t) {
+                    self.latest_message = Some(message_time);
                 }
             }
         }
+
+        let local_dt = message_time + chrono::Duration::hours(timezone_offset as i64);
+        self.hour_counts[local_dt.hour() as usize] += 1;
+        self.weekday_counts[local_dt.weekday().num_days_from_monday() as usize] += 1;
     }
-}
 
-fn analyze_messages(data: &ChatData, opt: &Opt) -> Statistics {
-    let mut stats = Statistics::new();
-    
-    for (_, messages) in data.0.iter() {
-        for msg in messages {
-            if let Some(ref user) = opt.user {
-                if (!msg.IsSender && msg.From != *user) && (msg.IsSender && msg.From == *user) {
-                    continue;
-                }
+    /// Folds another file's statistics into this one, summing counters,
+    /// unioning the per-key maps, and widening the time range.
+    fn merge(&mut self, other: Statistics) {
+        self.total_messages += other.total_messages;
+        self.messages_sent += other.messages_sent;
+        self.messages_received += other.messages_received;
+        self.saved_messages += other.saved_messages;
+
+        for (media_type, count) in other.media_type_counts {
+            *self.media_type_counts.entry(media_type).or_insert(0) += count;
+        }
+
+        for (user, (sent, received)) in other.users_interaction_counts {
+            let entry = self.users_interaction_counts.entry(user).or_insert((0, 0));
+            entry.0 += sent;
+            entry.1 += received;
+        }
+
+        for (user, other_latency) in other.reply_latency {
+            let entry = self
+                .reply_latency
+                .entry(user)
+                .or_insert_with(ReplyLatency::new);
+            for delta in other_latency.deltas_micros {
+                entry.record(delta);
             }
+        }
 
-            if let Some(ref from_date) = opt.from_date {
-                if msg.Created.split_whitespace().next().unwrap() < from_date {
-                    continue;
-                }
+        for hour in 0..24 {
+            self.hour_counts[hour] += other.hour_counts[hour];
+        }
+        for weekday in 0..7 {
+            self.weekday_counts[weekday] += other.weekday_counts[weekday];
+        }
+
+        if let Some(t) = other.earliest_message {
+            if self.earliest_message.is_none_or(|e| t < e) {
+                self.earliest_message = Some(t);
             }
+        }
 
-            if let Some(ref to_date) = opt.to_date {
-                if msg.Created.split_whitespace().next().unwrap() > to_date {
-                    continue;
+        if let Some(t) = other.latest_message {
+            if self.latest_message.is_none_or(|l| t > l) {
+                self.latest_message = Some(t);
+            }
+        }
+    }
+}
+
+/// Calendar-day filter bounds, resolved once per file from `--from-date`/
+/// `--to-date` so each message only needs a couple of `DateTime` comparisons
+/// rather than re-parsing strings.
+struct DateBounds {
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+}
+
+impl DateBounds {
+    fn from_opt(opt: &Opt) -> Self {
+        DateBounds {
+            from: opt
+                .from_date
+                .as_deref()
+                .and_then(|s| parse_date_bound(s, opt.timezone, false)),
+            to: opt
+                .to_date
+                .as_deref()
+                .and_then(|s| parse_date_bound(s, opt.timezone, true)),
+        }
+    }
+}
+
+/// Parses a `--from-date`/`--to-date` value as either a full timestamp or a
+/// bare `YYYY-MM-DD` date. Bare dates are interpreted as a calendar day in
+/// the `--timezone` local offset; `end_of_day` picks that day's last
+/// microsecond instead of its first, so `--to-date` is inclusive of the
+/// whole day.
+fn parse_date_bound(s: &str, timezone_offset: i32, end_of_day: bool) -> Option<DateTime<Utc>> {
+    if let Ok(dt) = DateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S %Z") {
+        return Some(dt.with_timezone(&Utc));
+    }
+    if let Ok(dt) = DateTime::parse_from_rfc3339(s) {
+        return Some(dt.with_timezone(&Utc));
+    }
+
+    let date = chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d").ok()?;
+    let local_time = if end_of_day {
+        date.and_hms_micro_opt(23, 59, 59, 999_999)?
+    } else {
+        date.and_hms_opt(0, 0, 0)?
+    };
+    let offset = chrono::Duration::hours(timezone_offset as i64);
+    Some(DateTime::<Utc>::from_naive_utc_and_offset(local_time, Utc) - offset)
+}
+
+/// Folds one conversation's messages into `stats`. Only this conversation's
+/// `Vec<Message>` needs to be resident at a time, so this is the unit of
+/// work fed by the streaming ingestion path in `analyze_file`.
+fn analyze_conversation(messages: &[Message], opt: &Opt, bounds: &DateBounds, stats: &mut Statistics) {
+    // Messages passing every filter except --user: reply latency needs both
+    // parties' messages to detect a sender flip, so --user (which narrows
+    // volume counting to one side of the conversation) must not also starve
+    // the latency walk down to a single party.
+    let mut latency_candidates: Vec<&Message> = Vec::new();
+
+    for msg in messages {
+        let message_time = DateTime::from_timestamp_micros(msg.created_microseconds);
+
+        if bounds.from.is_some() || bounds.to.is_some() {
+            match message_time {
+                Some(t) => {
+                    if bounds.from.is_some_and(|from| t < from) {
+                        continue;
+                    }
+                    if bounds.to.is_some_and(|to| t > to) {
+                        continue;
+                    }
                 }
+                None => continue,
             }
+        }
+
+        if opt.saved_only && !msg.IsSaved {
+            continue;
+        }
 
-            if opt.saved_only && !msg.IsSaved {
+        if let Some(ref media_type) = opt.media_type {
+            if msg.media_type != *media_type {
                 continue;
             }
+        }
 
-            if let Some(ref media_type) = opt.media_type {
-                if msg.media_type != *media_type {
-                    continue;
+        latency_candidates.push(msg);
+
+        if let Some(ref user) = opt.user {
+            if msg.From != *user {
+                continue;
+            }
+        }
+
+        stats.total_messages += 1;
+        if msg.IsSender {
+            stats.messages_received += 1;
+        } else {
+            stats.messages_sent += 1;
+        }
+
+        if msg.IsSaved {
+            stats.saved_messages += 1;
+        }
+
+        *stats.media_type_counts.entry(msg.media_type.clone()).or_insert(0) += 1;
+
+        let (sent, received) = stats
+            .users_interaction_counts
+            .entry(msg.From.clone())
+            .or_insert((0, 0));
+        if msg.IsSender {
+            *received += 1;
+        } else {
+            *sent += 1;
+        }
+
+        if let Some(message_time) = message_time {
+            stats.update_time_range(message_time, opt.timezone);
+        }
+    }
+
+    record_reply_latency(&latency_candidates, opt, &mut stats.reply_latency);
+}
+
+/// Walks one conversation's already-filtered messages in timestamp order
+/// and records a reply latency whenever the sender flips: the delta
+/// between the current message and the immediately preceding one,
+/// attributed to whichever user just replied. Non-positive deltas
+/// (out-of-order or duplicate timestamps) and gaps wider than
+/// `--session-gap` (a new conversation, not a reply) are both discarded.
+///
+/// `messages` must already reflect `--from-date`/`--to-date`/`--saved-only`/
+/// `--media-type`, the same as the volume counters, so a filtered run
+/// doesn't mix in latencies from excluded messages. `--user` is
+/// deliberately excluded from this set: it only narrows which user's
+/// volume gets counted, and applying it here would remove one side of
+/// every conversation, so the sender would never flip and latency would
+/// always come out empty.
+fn record_reply_latency(messages: &[&Message], opt: &Opt, result: &mut HashMap<String, ReplyLatency>) {
+    let session_gap_micros = (opt.session_gap * 3_600_000_000.0) as i64;
+
+    let mut sorted: Vec<&Message> = messages.to_vec();
+    sorted.sort_by_key(|m| m.created_microseconds);
+
+    let mut last: Option<&Message> = None;
+    for msg in sorted {
+        if let Some(prev) = last {
+            if prev.IsSender != msg.IsSender {
+                let delta = msg.created_microseconds - prev.created_microseconds;
+                if delta > 0 && delta <= session_gap_micros {
+                    result
+                        .entry(msg.From.clone())
+                        .or_insert_with(ReplyLatency::new)
+                        .record(delta);
                 }
             }
+        }
+        last = Some(msg);
+    }
+}
+
+/// Renders a computed `Statistics` to stdout in some output format.
+///
+/// Mirrors the encode/decode split used by multi-format log tools: each
+/// format is a small, independent unit that knows only how to turn
+/// `Statistics` into text, so adding a new format means adding a new impl
+/// rather than branching inside `print_statistics`.
+trait OutputFormat {
+    fn print(&self, stats: &Statistics, opt: &Opt);
+}
 
-            stats.total_messages += 1;
-            if msg.IsSender {
-                stats.messages_received += 1;
-            } else {
-                stats.messages_sent += 1;
+struct TextFormat;
+struct JsonFormat;
+struct CsvFormat;
+
+impl OutputFormat for TextFormat {
+    fn print(&self, stats: &Statistics, opt: &Opt) {
+        println!("\nSnapchat Chat Statistics:");
+        println!("-------------------------");
+        println!("Total messages: {}", stats.total_messages);
+        println!("Messages sent: {}", stats.messages_sent);
+        println!("Messages received: {}", stats.messages_received);
+        println!("Saved messages: {}", stats.saved_messages);
+
+        if let (Some(earliest), Some(latest)) = (stats.earliest_message, stats.latest_message) {
+            let duration = latest.signed_duration_since(earliest);
+            let days = duration.num_days();
+            if days > 0 {
+                println!("\nDate range: {} days", days);
+                println!("Average messages per day: {:.2}", stats.total_messages as f64 / days as f64);
             }
+        }
 
-            if msg.IsSaved {
-                stats.saved_messages += 1;
+        if opt.detailed {
+            println!("\nMedia Type Breakdown:");
+            for (media_type, count) in &stats.media_type_counts {
+                println!("  {}: {}", media_type, count);
             }
 
-            *stats.media_type_counts.entry(msg.media_type.clone()).or_insert(0) += 1;
+            println!("\nUser Interaction Breakdown:");
+            for (user, (sent, received)) in &stats.users_interaction_counts {
+                println!("  {}:", user);
+                println!("    Sent: {}", sent);
+                println!("    Received: {}", received);
+            }
+
+            println!("\nReply Latency:");
+            for (user, latency) in &stats.reply_latency {
+                if latency.count == 0 {
+                    continue;
+                }
+                println!("  {}:", user);
+                println!("    Avg reply time: {}", format_duration_micros(latency.mean_micros as i64));
+                if let Some(median) = latency.median_micros() {
+                    println!("    Median reply time: {}", format_duration_micros(median));
+                }
+                if let Some(p90) = latency.p90_micros() {
+                    println!("    P90 reply time: {}", format_duration_micros(p90));
+                }
+            }
+        }
 
-            let (sent, received) = stats.users_interaction_counts
-                .entry(msg.From.clone())
-                .or_insert((0, 0));
-            if msg.IsSender {
-                *received += 1;
-            } else {
-                *sent += 1;
+        if opt.histogram {
+            let max_hour = stats.hour_counts.iter().copied().max().unwrap_or(0);
+            println!("\nActivity by Hour of Day:");
+            for (hour, count) in stats.hour_counts.iter().enumerate() {
+                println!("  {:02}:00 {}", hour, histogram_bar(*count, max_hour));
             }
 
-            stats.update_time_range(&msg.Created);
+            const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+            let max_weekday = stats.weekday_counts.iter().copied().max().unwrap_or(0);
+            println!("\nActivity by Day of Week:");
+            for (i, count) in stats.weekday_counts.iter().enumerate() {
+                println!("  {} {}", WEEKDAYS[i], histogram_bar(*count, max_weekday));
+            }
         }
     }
+}
 
-    stats
+impl OutputFormat for JsonFormat {
+    fn print(&self, stats: &Statistics, _opt: &Opt) {
+        match serde_json::to_string_pretty(stats) {
+            Ok(json) => println!("{}", json),
+            Err(e) => eprintln!("Failed to serialize statistics as JSON: {}", e),
+        }
+    }
 }
 
-fn print_statistics(stats: &Statistics, opt: &Opt) {
-    println!("\nSnapchat Chat Statistics:");
-    println!("-------------------------");
-    println!("Total messages: {}", stats.total_messages);
-    println!("Messages sent: {}", stats.messages_sent);
-    println!("Messages received: {}", stats.messages_received);
-    println!("Saved messages: {}", stats.saved_messages);
+/// Quotes a CSV field per RFC 4180 if it contains a comma, quote, or
+/// newline, doubling any embedded quotes.
+fn csv_field(s: &str) -> String {
+    if s.contains(',') || s.contains('"') || s.contains('\n') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
 
-    if let (Some(earliest), Some(latest)) = (stats.earliest_message, stats.latest_message) {
-        let duration = latest.signed_duration_since(earliest);
-        let days = duration.num_days();
-        if days > 0 {
-            println!("\nDate range: {} days", days);
-            println!("Average messages per day: {:.2}", stats.total_messages as f64 / days as f64);
+impl OutputFormat for CsvFormat {
+    fn print(&self, stats: &Statistics, _opt: &Opt) {
+        println!("user,sent,received");
+        for (user, (sent, received)) in &stats.users_interaction_counts {
+            println!("{},{},{}", csv_field(user), sent, received);
         }
+        println!(
+            "TOTAL,{},{}",
+            stats.messages_sent, stats.messages_received
+        );
     }
+}
 
-    if opt.detailed {
-        println!("\nMedia Type Breakdown:");
-        for (media_type, count) in &stats.media_type_counts {
-            println!("  {}: {}", media_type, count);
-        }
+fn print_statistics(stats: &Statistics, opt: &Opt) {
+    let formatter: Box<dyn OutputFormat> = match opt.format {
+        Format::Text => Box::new(TextFormat),
+        Format::Json => Box::new(JsonFormat),
+        Format::Csv => Box::new(CsvFormat),
+    };
+    formatter.print(stats, opt);
+}
 
-        println!("\nUser Interaction Breakdown:");
-        for (user, (sent, received)) in &stats.users_interaction_counts {
-            println!("  {}:", user);
-            println!("    Sent: {}", sent);
-            println!("    Received: {}", received);
+/// Streams one export file's `{conversation_id: [Message, ...]}` map off
+/// disk, analyzing and discarding each conversation's messages as they are
+/// decoded so the whole file is never resident in memory at once.
+fn analyze_file(
+    path: &std::path::Path,
+    opt: &Opt,
+) -> Result<Statistics, Box<dyn std::error::Error + Send + Sync>> {
+    let file = fs::File::open(path)?;
+    let reader = std::io::BufReader::new(file);
+    let mut stats = Statistics::new();
+    let bounds = DateBounds::from_opt(opt);
+
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    deserializer.deserialize_map(ChatDataVisitor {
+        stats: &mut stats,
+        opt,
+        bounds: &bounds,
+    })?;
+
+    Ok(stats)
+}
+
+/// Visits the top-level export map one conversation at a time, handing each
+/// decoded `Vec<Message>` to `analyze_conversation` before moving on to the
+/// next key.
+struct ChatDataVisitor<'a> {
+    stats: &'a mut Statistics,
+    opt: &'a Opt,
+    bounds: &'a DateBounds,
+}
+
+impl<'de, 'a> serde::de::Visitor<'de> for ChatDataVisitor<'a> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a map of conversation id to an array of messages")
+    }
+
+    fn visit_map<M>(self, mut map: M) -> Result<Self::Value, M::Error>
+    where
+        M: serde::de::MapAccess<'de>,
+    {
+        while let Some(_conversation_id) = map.next_key::<String>()? {
+            let messages: Vec<Message> = map.next_value()?;
+            analyze_conversation(&messages, self.opt, self.bounds, self.stats);
         }
+        Ok(())
     }
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let opt = Opt::from_args();
 
-    let data_str = fs::read_to_string(&opt.input)?;
-    let chat_data: ChatData = serde_json::from_str(&data_str)?;
+    let paths = glob::glob(&opt.input)?.collect::<Result<Vec<_>, _>>()?;
+    if paths.is_empty() {
+        return Err(format!("no files matched input pattern: {}", opt.input).into());
+    }
 
-    let stats = analyze_messages(&chat_data, &opt);
+    let per_file: Vec<Statistics> = paths
+        .par_iter()
+        .map(|path| -> Result<Statistics, Box<dyn std::error::Error + Send + Sync>> {
+            analyze_file(path, &opt)
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| -> Box<dyn std::error::Error> { e.to_string().into() })?;
+
+    let stats = per_file
+        .into_iter()
+        .fold(Statistics::new(), |mut acc, s| {
+            acc.merge(s);
+            acc
+        });
 
     print_statistics(&stats, &opt);
 